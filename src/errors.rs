@@ -15,9 +15,18 @@ error_chain! {
 
     errors {
         GenericError
-        TemplateDoesNotExist(t: String) {
+        TemplateDoesNotExist(t: String, suggestions: Vec<String>) {
             description("template not in index")
-            display("Could not find template {} in the index", t)
+            display("Could not find template {} in the index{}", t,
+                    if suggestions.is_empty() {
+                        String::new()
+                    } else {
+                        format!("; did you mean {}?",
+                                suggestions.iter()
+                                           .map(|s| format!("`{}`", s))
+                                           .collect::<Vec<_>>()
+                                           .join(", "))
+                    })
         }
         TemplateNotFound(t: String) {
             description("template not found locally")
@@ -39,5 +48,21 @@ error_chain! {
             description("directory exists")
             display("The project {} already exists", t)
         }
+        TemplateRenderError(t: String) {
+            description("could not render template")
+            display("Error rendering template: {}", t)
+        }
+        HookError(script: String, code: i32) {
+            description("hook script failed")
+            display("Hook script {} exited with status {}", script, code)
+        }
+        GlobPatternError(t: String) {
+            description("invalid glob pattern")
+            display("Invalid glob pattern {}", t)
+        }
+        CredentialError(url: String, method: String) {
+            description("authentication failed")
+            display("Could not authenticate to {} ({})", url, method)
+        }
     }
 }
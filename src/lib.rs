@@ -9,24 +9,35 @@ extern crate git2;
 extern crate serde_json;
 extern crate clap;
 extern crate toml;
+extern crate regex;
+extern crate time;
+extern crate glob;
 
 mod errors;
 
 use std::env;
 use std::path::{Path, PathBuf};
 use std::fs::{DirBuilder, File, OpenOptions, self};
+use std::process::Command;
 use std::collections::HashMap;
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
 
 use cargo::util;
+use cargo::util::config::ConfigValue;
 use git2::Repository;
 use git2::Config as GitConfig;
 use clap::{App, Arg, ArgSettings};
+use regex::Regex;
+use glob::Pattern;
 
 use errors::*;
 
 const DEFAULT_INDEX: &'static str = "https://github.com/rusttemplates/templates";
 
+// Name of the optional manifest a template ships in its root to declare
+// substitution variables. It is never written into the generated project.
+const MANIFEST_NAME: &'static str = "cargo-template.toml";
+
 fn ensure_exists<P: AsRef<Path>>(p: P) -> Result<()> {
         let p = p.as_ref();
         let _ = DirBuilder::new().recursive(true).create(p)?;
@@ -38,6 +49,8 @@ pub struct Config {
     pub index_path: PathBuf,
     pub templates_path: PathBuf,
     pub resolved_index_path: Option<PathBuf>,
+    pub favorites: HashMap<String, String>,
+    pub registry_token: Option<String>,
 }
 
 impl Config {
@@ -60,11 +73,25 @@ impl Config {
         let templates_path = config_dir.join("templates");
         ensure_exists(&templates_path)?;
 
+        let mut favorites = HashMap::new();
+        if let Some(table) = cargo_config.get_table("template.favorites")? {
+            for (name, value) in table.val {
+                if let ConfigValue::String(s, _) = value {
+                    favorites.insert(name, s);
+                }
+            }
+        }
+
+        let registry_token = cargo_config.get_string("template.registry.token")?
+                                         .map(|val| val.val);
+
         Ok(Config {
             index: index,
             index_path: index_path,
             templates_path: templates_path,
             resolved_index_path: None,
+            favorites: favorites,
+            registry_token: registry_token,
         })
     }
 }
@@ -107,14 +134,78 @@ impl Iterator for IndexIter {
     }
 }
 
+// Clone a repository with libgit2 credential handling wired up: ssh-agent
+// then an `~/.ssh` key pair for SSH remotes, and a token (from the argument,
+// `GITHUB_TOKEN`, or git's credential helper) for HTTPS remotes. `CredentialError`
+// carries the URL and the methods that were attempted.
+fn authenticated_clone(url: &str, dest: &Path, config_token: Option<&str>) -> Result<Repository> {
+    use git2::{Cred, CredentialType, RemoteCallbacks, FetchOptions};
+    use git2::build::RepoBuilder;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let token = env::var("GITHUB_TOKEN").ok()
+                    .or_else(|| config_token.map(|t| t.to_string()));
+
+    let attempted: Rc<RefCell<Vec<&'static str>>> = Rc::new(RefCell::new(Vec::new()));
+    let tried = attempted.clone();
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.credentials(move |_url, username, allowed| {
+        let username = username.unwrap_or("git");
+        if allowed.contains(CredentialType::SSH_KEY) {
+            tried.borrow_mut().push("ssh-agent");
+            if let Ok(cred) = Cred::ssh_key_from_agent(username) {
+                return Ok(cred);
+            }
+            if let Ok(home) = env::var("HOME") {
+                let key = Path::new(&home).join(".ssh").join("id_rsa");
+                let pubkey = Path::new(&home).join(".ssh").join("id_rsa.pub");
+                if key.exists() {
+                    tried.borrow_mut().push("ssh-key");
+                    let pubkey = if pubkey.exists() { Some(pubkey.as_path()) } else { None };
+                    return Cred::ssh_key(username, pubkey, &key, None);
+                }
+            }
+        }
+        if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+            if let Some(ref t) = token {
+                tried.borrow_mut().push("token");
+                return Cred::userpass_plaintext(t, "");
+            }
+            tried.borrow_mut().push("credential-helper");
+            if let Ok(config) = GitConfig::open_default() {
+                if let Ok(cred) = Cred::credential_helper(&config, _url, Some(username)) {
+                    return Ok(cred);
+                }
+            }
+        }
+        if allowed.contains(CredentialType::DEFAULT) {
+            tried.borrow_mut().push("default");
+            return Cred::default();
+        }
+        Err(git2::Error::from_str("no suitable authentication method available"))
+    });
+
+    let mut fetch_options = FetchOptions::new();
+    fetch_options.remote_callbacks(callbacks);
+    let mut builder = RepoBuilder::new();
+    builder.fetch_options(fetch_options);
+    builder.clone(url, dest).map_err(|e| {
+        let methods = attempted.borrow().join(", ");
+        ErrorKind::CredentialError(url.to_string(), format!("tried [{}]: {}", methods, e)).into()
+    })
+}
+
 struct IndexLoader<'a> {
     index: &'a Path,
+    token: Option<String>,
 }
 
 impl<'a> IndexLoader<'a> {
-    fn new(index: &'a Path) -> IndexLoader<'a> {
+    fn new(index: &'a Path, token: Option<String>) -> IndexLoader<'a> {
         IndexLoader {
             index: index,
+            token: token,
         }
     }
 
@@ -139,7 +230,7 @@ impl<'a> IndexLoader<'a> {
     fn clone_index(&self, source: &str) -> Result<PathBuf> {
         // hacky and not-sufficient way to turn a url into a valid (single) directory name
         let p = self.index.join(self.url_to_repo_dir(source));
-        let _ = Repository::clone(source, &p)?;
+        let _ = authenticated_clone(source, &p, self.token.as_ref().map(|s| s.as_str()))?;
         debug!("cloned index at {:?}", &p);
         Ok(p)
     }
@@ -150,7 +241,7 @@ impl<'a> IndexLoader<'a> {
 }
 
 fn get_index(config: &mut Config, frozen: bool) -> Result<HashMap<String, String>> {
-    let i = IndexLoader::new(&config.index_path);
+    let i = IndexLoader::new(&config.index_path, config.registry_token.clone());
     if let Ok(p) = i.update_or_clone(&config.index, frozen) {
         config.resolved_index_path = Some(p);
     }
@@ -168,20 +259,342 @@ fn get_index(config: &mut Config, frozen: bool) -> Result<HashMap<String, String
     Ok(index_members)
 }
 
-fn get_template<P: AsRef<Path>>(name: &str, url: &str, templates_dir: P, frozen: bool) -> Result<PathBuf> {
+// Classic Levenshtein edit distance, using a single row of O(min(len))
+// working memory.
+fn levenshtein(a: &str, b: &str) -> usize {
+    let (a, b) = if a.chars().count() < b.chars().count() { (b, a) } else { (a, b) };
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut dp: Vec<usize> = (0..=b.len()).collect();
+    for (i, ai) in a.iter().enumerate() {
+        let mut prev = dp[0];
+        dp[0] = i + 1;
+        for (j, bj) in b.iter().enumerate() {
+            let diag = prev;
+            prev = dp[j + 1];
+            let cost = if ai != bj { 1 } else { 0 };
+            dp[j + 1] = ::std::cmp::min(::std::cmp::min(dp[j + 1] + 1, dp[j] + 1), diag + cost);
+        }
+    }
+    dp[b.len()]
+}
+
+// Find the index names closest to a mistyped template name, nearest first.
+fn template_suggestions(name: &str, index: &HashMap<String, String>) -> Vec<String> {
+    let threshold = ::std::cmp::max(2, name.len() / 3);
+    let mut scored: Vec<(usize, String)> = index.keys()
+        .map(|key| (levenshtein(name, key), key.clone()))
+        .filter(|&(dist, _)| dist <= threshold)
+        .collect();
+    scored.sort_by(|a, b| a.0.cmp(&b.0));
+    scored.into_iter().take(3).map(|(_, key)| key).collect()
+}
+
+fn get_template<P: AsRef<Path>>(name: &str, url: &str, templates_dir: P, frozen: bool, token: Option<&str>) -> Result<PathBuf> {
     let templates_dir = templates_dir.as_ref();
     let location = templates_dir.join(name);
     if !location.exists() {
         if frozen {
             return Err(ErrorKind::TemplateNotFound(name.into()).into())
         }
-        let _ = Repository::clone(url, &location);
+        let _ = authenticated_clone(url, &location, token)?;
     }
 
     Ok(location)
 }
 
-fn copy_dir<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> Result<()> {
+// A single user-declared variable from the template manifest.
+#[derive(Debug, Default)]
+struct Variable {
+    prompt: Option<String>,
+    default: Option<String>,
+    validate: Option<String>,
+}
+
+// Hook scripts a template can run around generation. Paths are relative to
+// the template root.
+#[derive(Debug, Default)]
+struct Hooks {
+    pre: Vec<String>,
+    post: Vec<String>,
+}
+
+// Raw include/exclude glob lists from the manifest's `[template]` section.
+#[derive(Debug, Default)]
+struct TemplateSection {
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+// Everything `cargo-template.toml` can declare. Parsed manually out of a
+// `toml::Table` to match the toml 0.2 idiom used by `edit_cargo_toml`.
+#[derive(Debug, Default)]
+struct Manifest {
+    variables: HashMap<String, Variable>,
+    hooks: Hooks,
+    template: TemplateSection,
+}
+
+// Compiled include/exclude globs with their precedence rules baked into
+// `matches`.
+struct TemplateRules {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl TemplateRules {
+    // Compile the manifest's glob lists, reporting the first bad pattern.
+    fn compile(section: &TemplateSection) -> Result<TemplateRules> {
+        fn compile_all(globs: &[String]) -> Result<Vec<Pattern>> {
+            globs.iter().map(|g| {
+                Pattern::new(g).map_err(|e| {
+                    ErrorKind::GlobPatternError(format!("{}: {}", g, e)).into()
+                })
+            }).collect()
+        }
+        Ok(TemplateRules {
+            include: compile_all(&section.include)?,
+            exclude: compile_all(&section.exclude)?,
+        })
+    }
+
+    // Whether a path (relative to the template root) should be copied.
+    // Exclude wins over include; with no include list everything unmatched is
+    // included, but once an include list exists unmatched paths are dropped.
+    fn matches(&self, rel: &Path) -> bool {
+        if self.exclude.iter().any(|p| pattern_matches(p, rel)) {
+            return false;
+        }
+        if self.include.is_empty() {
+            return true;
+        }
+        self.include.iter().any(|p| pattern_matches(p, rel))
+    }
+
+    // A directory is only pruned outright when an exclude pattern matches it;
+    // include filtering is applied per file so parents of included files are
+    // still descended into.
+    fn allows_dir(&self, rel: &Path) -> bool {
+        !self.exclude.iter().any(|p| pattern_matches(p, rel))
+    }
+}
+
+// `Pattern::matches` compares against the whole relative path, so a literal
+// pattern such as `Cargo.lock` would never match a nested `sub/Cargo.lock`.
+// Match the pattern against the full relative path *and* the final path
+// component, so authors can write `*.rs` or `Cargo.lock` without spelling out
+// `**/` while `src/*.rs` still works against the full path.
+fn pattern_matches(pattern: &Pattern, rel: &Path) -> bool {
+    if pattern.matches(&rel.to_string_lossy()) {
+        return true;
+    }
+    match rel.file_name() {
+        Some(name) => pattern.matches(&name.to_string_lossy()),
+        None => false,
+    }
+}
+
+fn load_manifest<P: AsRef<Path>>(dir: P) -> Result<Manifest> {
+    let path = dir.as_ref().join(MANIFEST_NAME);
+    if !path.exists() {
+        return Ok(Manifest::default());
+    }
+    let mut contents = String::new();
+    File::open(&path)?.read_to_string(&mut contents)?;
+    let mut parser = toml::Parser::new(&contents);
+    let value = match parser.parse() {
+        Some(val) => val,
+        None => return Err(ErrorKind::TomlParseError(path.to_string_lossy().into_owned()).into()),
+    };
+
+    let mut manifest = Manifest::default();
+    if let Some(&toml::Value::Table(ref vars)) = value.get("variables") {
+        for (name, def) in vars {
+            let mut var = Variable::default();
+            if let toml::Value::Table(ref t) = *def {
+                if let Some(&toml::Value::String(ref s)) = t.get("prompt") {
+                    var.prompt = Some(s.clone());
+                }
+                if let Some(&toml::Value::String(ref s)) = t.get("default") {
+                    var.default = Some(s.clone());
+                }
+                if let Some(&toml::Value::String(ref s)) = t.get("validate") {
+                    var.validate = Some(s.clone());
+                }
+            }
+            manifest.variables.insert(name.clone(), var);
+        }
+    }
+    if let Some(&toml::Value::Table(ref hooks)) = value.get("hooks") {
+        manifest.hooks.pre = toml_string_list(hooks.get("pre"));
+        manifest.hooks.post = toml_string_list(hooks.get("post"));
+    }
+    if let Some(&toml::Value::Table(ref tmpl)) = value.get("template") {
+        manifest.template.include = toml_string_list(tmpl.get("include"));
+        manifest.template.exclude = toml_string_list(tmpl.get("exclude"));
+    }
+    Ok(manifest)
+}
+
+// Pull a list of strings out of an optional toml value, ignoring non-string
+// entries.
+fn toml_string_list(value: Option<&toml::Value>) -> Vec<String> {
+    match value {
+        Some(&toml::Value::Array(ref arr)) => arr.iter().filter_map(|v| match *v {
+            toml::Value::String(ref s) => Some(s.clone()),
+            _ => None,
+        }).collect(),
+        _ => Vec::new(),
+    }
+}
+
+// Run a list of hook scripts through the system shell, with the variable
+// context exported as `CARGO_TEMPLATE_*` environment variables and the working
+// directory set to the generated project.
+fn run_hooks(scripts: &[String], template_dir: &Path, project_dir: &Path,
+             ctx: &HashMap<String, String>) -> Result<()> {
+    let (shell, flag) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+    for script in scripts {
+        let script_path = template_dir.join(script);
+        debug!("running hook {:?}", script_path);
+        let mut cmd = Command::new(shell);
+        cmd.arg(flag)
+           .arg(script_path.to_string_lossy().into_owned())
+           .current_dir(project_dir);
+        for (key, val) in ctx {
+            cmd.env(format!("CARGO_TEMPLATE_{}", key.to_uppercase()), val);
+        }
+        let status = cmd.status()?;
+        if !status.success() {
+            return Err(ErrorKind::HookError(script.clone(), status.code().unwrap_or(-1)).into());
+        }
+    }
+    Ok(())
+}
+
+// Check a resolved value against the variable's optional validation regex.
+fn validate_variable(name: &str, value: &str, var: &Variable) -> Result<()> {
+    if let Some(ref pat) = var.validate {
+        let re = Regex::new(pat)
+            .map_err(|e| ErrorKind::TemplateRenderError(
+                format!("invalid validation regex for `{}`: {}", name, e)))?;
+        if !re.is_match(value) {
+            return Err(ErrorKind::TemplateRenderError(
+                format!("value \"{}\" for `{}` does not match /{}/", value, name, pat)).into());
+        }
+    }
+    Ok(())
+}
+
+// Ask the user for a variable on stdin, re-prompting until validation passes.
+fn prompt_variable(name: &str, var: &Variable) -> Result<String> {
+    let label = var.prompt.clone().unwrap_or_else(|| name.to_string());
+    loop {
+        match var.default {
+            Some(ref d) => print!("{} [{}]: ", label, d),
+            None => print!("{}: ", label),
+        }
+        io::stdout().flush()?;
+        let mut line = String::new();
+        io::stdin().read_line(&mut line)?;
+        let trimmed = line.trim();
+        let value = if trimmed.is_empty() {
+            match var.default {
+                Some(ref d) => d.clone(),
+                None => continue,
+            }
+        } else {
+            trimmed.to_string()
+        };
+        match validate_variable(name, &value, var) {
+            Ok(()) => return Ok(value),
+            Err(e) => {
+                let mut stderr = io::stderr();
+                let _ = writeln!(stderr, "{}", e);
+            }
+        }
+    }
+}
+
+// Seed the substitution context with the built-in variables every template
+// can rely on, regardless of what the manifest declares.
+fn builtin_context(project_name: &str, author: &str) -> HashMap<String, String> {
+    let mut ctx = HashMap::new();
+    ctx.insert("project_name".to_string(), project_name.to_string());
+    ctx.insert("crate_name".to_string(), project_name.replace('-', "_"));
+    ctx.insert("authors".to_string(), author.to_string());
+    ctx.insert("os".to_string(), env::consts::OS.to_string());
+    let date = time::strftime("%Y-%m-%d", &time::now()).unwrap_or_default();
+    ctx.insert("date".to_string(), date);
+    ctx
+}
+
+// Fill in the user-declared variables, preferring values supplied on the CLI
+// and falling back to prompting (or, under `--frozen`, to the declared
+// default). Built-in variables are never prompted for.
+fn resolve_variables(manifest: &Manifest, ctx: &mut HashMap<String, String>,
+                     defines: &HashMap<String, String>, frozen: bool) -> Result<()> {
+    for (name, var) in &manifest.variables {
+        if let Some(value) = defines.get(name) {
+            validate_variable(name, value, var)?;
+            ctx.insert(name.clone(), value.clone());
+            continue;
+        }
+        if ctx.contains_key(name) {
+            continue;
+        }
+        let value = if frozen {
+            match var.default {
+                Some(ref d) => d.clone(),
+                None => return Err(ErrorKind::TemplateRenderError(
+                    format!("variable `{}` has no value and cannot be prompted for under --frozen", name)).into()),
+            }
+        } else {
+            prompt_variable(name, var)?
+        };
+        validate_variable(name, &value, var)?;
+        ctx.insert(name.clone(), value);
+    }
+    Ok(())
+}
+
+// A minimal mustache-style renderer: replaces `{{ key }}` with its value from
+// the context, leaving unknown placeholders untouched.
+fn render(input: &str, ctx: &HashMap<String, String>) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        if let Some(end) = after.find("}}") {
+            let key = after[..end].trim();
+            match ctx.get(key) {
+                Some(val) => out.push_str(val),
+                None => {
+                    out.push_str("{{");
+                    out.push_str(&after[..end]);
+                    out.push_str("}}");
+                }
+            }
+            rest = &after[end + 2..];
+        } else {
+            out.push_str("{{");
+            rest = after;
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+// Files containing a NUL byte in their leading bytes are treated as binary and
+// copied verbatim rather than rendered.
+fn is_binary(bytes: &[u8]) -> bool {
+    bytes.iter().take(8000).any(|&b| b == 0)
+}
+
+fn copy_dir<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q, root: &Path,
+                                            rules: &TemplateRules,
+                                            ctx: &HashMap<String, String>) -> Result<()> {
     let from = from.as_ref();
     let to = to.as_ref();
 
@@ -196,20 +609,45 @@ fn copy_dir<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> Result<()> {
         let entry = entry?;
         let file_name = entry.file_name();
         let lossy = file_name.to_string_lossy();
-        if lossy == ".git" {
+        if lossy == ".git" || lossy == MANIFEST_NAME {
             continue;
         }
         let path = entry.path();
+        let rel = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+        // Destination names may themselves contain placeholders.
+        let dest_name = render(&lossy, ctx);
 
         if path.is_dir() {
-            let new_to = to.join(&file_name);
+            if !rules.allows_dir(&rel) {
+                debug!("skipping excluded directory {:?}", rel);
+                continue;
+            }
+            let new_to = to.join(&dest_name);
             ensure_exists(&new_to)?;
             debug!("from {} to {}", path.to_str().unwrap(), new_to.to_str().unwrap());
-            copy_dir(path, new_to)?;
+            copy_dir(path, new_to, root, rules, ctx)?;
         } else if path.is_file() {
-            let new_to = to.join(&file_name);
-            debug!("copy {} to {}", path.to_str().unwrap(), new_to.to_str().unwrap());
-            fs::copy(&path, &new_to)?;
+            if !rules.matches(&rel) {
+                debug!("skipping filtered file {:?}", rel);
+                continue;
+            }
+            let new_to = to.join(&dest_name);
+            debug!("render {} to {}", path.to_str().unwrap(), new_to.to_str().unwrap());
+            let mut buf = Vec::new();
+            File::open(&path)?.read_to_end(&mut buf)?;
+            {
+                let mut dest = File::create(&new_to)?;
+                if is_binary(&buf) {
+                    dest.write_all(&buf)?;
+                } else {
+                    let rendered = render(&String::from_utf8_lossy(&buf), ctx);
+                    dest.write_all(rendered.as_bytes())?;
+                }
+            }
+            // Preserve the source file's mode bits the way the baseline
+            // `fs::copy` did, so templates can ship executable scripts or git
+            // hooks without them landing as plain 0644.
+            fs::set_permissions(&new_to, fs::metadata(&path)?.permissions())?;
         } else {
             error!("Oops, this isn't a directory or a file, I don't know how to handle this so I'm just gonna ignore it");
             error!("problem entry: {:?}", path.to_str());
@@ -312,65 +750,306 @@ fn find_cargo_toml<P: AsRef<Path>>(project_dir: P, project_name: &str,
     Ok(())
 }
 
+// Heuristic for telling a favorite that points at a git repository from one
+// that names an index entry.
+fn looks_like_git_url(s: &str) -> bool {
+    s.contains("://") || s.starts_with("git@") || s.ends_with(".git")
+}
+
+// Print the merged set of favorites and index templates to stdout.
+fn list_templates(config: &mut Config, frozen: bool) -> Result<()> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    if !config.favorites.is_empty() {
+        writeln!(out, "Favorites:")?;
+        let mut names: Vec<&String> = config.favorites.keys().collect();
+        names.sort();
+        for name in names {
+            writeln!(out, "    {} = {}", name, config.favorites[name])?;
+        }
+    }
+    let index = get_index(config, frozen)?;
+    writeln!(out, "Templates:")?;
+    let mut names: Vec<&String> = index.keys().collect();
+    names.sort();
+    for name in names {
+        writeln!(out, "    {}", name)?;
+    }
+    Ok(())
+}
+
+// A binary crate keeps its `Cargo.lock` under version control; a library
+// crate ignores it. Detect which kind of project was generated.
+fn project_is_bin(project_dir: &Path) -> bool {
+    project_dir.join("src").join("main.rs").exists() || project_dir.join("src").join("bin").is_dir()
+}
+
+// Initialize a git repository in the generated project and make the initial
+// commit, writing a default `.gitignore` when the template didn't ship one.
+fn init_vcs(project_dir: &Path, author_name: &str, author_email: &Option<String>,
+            is_bin: bool) -> Result<()> {
+    let gitignore = project_dir.join(".gitignore");
+    if !gitignore.exists() {
+        let mut contents = String::from("/target\n**/*.rs.bk\n");
+        if !is_bin {
+            contents.push_str("Cargo.lock\n");
+        }
+        File::create(&gitignore)?.write_all(contents.as_bytes())?;
+    }
+
+    let repo = Repository::init(project_dir)?;
+    let mut index = repo.index()?;
+    index.add_all(["*"].iter(), git2::IndexAddOption::DEFAULT, None)?;
+    index.write()?;
+    let tree_id = index.write_tree()?;
+    let tree = repo.find_tree(tree_id)?;
+    let email = author_email.as_ref().map(|s| s.as_str()).unwrap_or("");
+    let sig = git2::Signature::now(author_name, email)?;
+    repo.commit(Some("HEAD"), &sig, &sig, "Initial commit", &tree, &[])?;
+    Ok(())
+}
+
 fn cli() -> App<'static, 'static> {
     App::new("cargo-template")
         .about("initialize new cargo projects from a predefined template")
         .arg(Arg::with_name("frozen")
                 .long("frozen")
                 .help("Asserts that we shouldn't touch the network"))
+        .arg(Arg::with_name("define")
+                .long("define")
+                .short("D")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Set a template variable, as KEY=VALUE"))
+        .arg(Arg::with_name("allow-hooks")
+                .long("allow-hooks")
+                .help("Permit the template's pre/post hook scripts to run"))
+        .arg(Arg::with_name("list")
+                .long("list")
+                .help("List the available index templates and favorites, then exit"))
+        .arg(Arg::with_name("vcs")
+                .long("vcs")
+                .takes_value(true)
+                .possible_values(&["git", "none"])
+                .default_value("git")
+                .help("Initialize a version control repository for the new project"))
         .arg(Arg::with_name("CARGO_ADDS_THIS")
                 .set(ArgSettings::Hidden)
                 .required(true)
                 .index(1))
         .arg(Arg::with_name("TEMPLATE")
                 .help("The template to use")
-                .required(true)
+                .required_unless("list")
                 .index(2))
         .arg(Arg::with_name("NAME")
                 .help("the project name")
-                .required(true)
+                .required_unless("list")
                 .index(3))
 }
 
 pub fn main() -> Result<()> {
     let matches = cli().get_matches();
     let frozen = matches.is_present("frozen");
-    let template = matches.value_of("TEMPLATE").unwrap(); // If we've gotten here, clap has verified that we have this
+
+    if matches.is_present("list") {
+        let mut config = Config::new()?;
+        return list_templates(&mut config, frozen);
+    }
+
+    let requested = matches.value_of("TEMPLATE").unwrap(); // If we've gotten here, clap has verified that we have this
     let project_name = matches.value_of("NAME").unwrap();
+    let mut defines: HashMap<String, String> = HashMap::new();
+    if let Some(values) = matches.values_of("define") {
+        for def in values {
+            let mut parts = def.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(key), Some(val)) => { defines.insert(key.trim().to_string(), val.to_string()); },
+                _ => return Err(ErrorKind::TemplateRenderError(
+                    format!("invalid --define `{}`, expected KEY=VALUE", def)).into()),
+            }
+        }
+    }
     let cwd = env::current_dir()?;
     let project_dir = cwd.join(project_name);
     if project_dir.exists() {
         return Err(ErrorKind::ExistsError(project_dir.to_string_lossy().into_owned()).into());
     }
-    debug!("template: {:?}", template);
+    debug!("template: {:?}", requested);
     debug!("project name: {:?}", project_name);
     let mut config = Config::new()?;
-    let metadata = fs::metadata(template);
-    let from = if metadata.is_ok() && metadata.unwrap().is_dir() {
-        debug!("found template on filesystem");
-        Path::new(template).to_path_buf()
-    } else {
-        let index = get_index(&mut config, frozen)?;
-        let location = match index.get(template) {
-            Some(loc) => loc,
-            None => return Err(ErrorKind::TemplateDoesNotExist(template.into()).into())
-        };
-        debug!("template url is {:?}", location);
-        let from = match get_template(template, location, &config.templates_path, frozen) {
+
+    // Favorites take precedence over both the filesystem and the index. A
+    // favorite may be a git URL (cloned directly) or an alias for an index
+    // entry.
+    let (template, favorite_url): (String, Option<String>) = match config.favorites.get(requested) {
+        Some(val) if looks_like_git_url(val) => (requested.to_string(), Some(val.clone())),
+        Some(val) => (val.clone(), None),
+        None => (requested.to_string(), None),
+    };
+
+    let from = if let Some(url) = favorite_url {
+        debug!("cloning favorite {} from {}", requested, url);
+        match get_template(requested, &url, &config.templates_path, frozen, config.registry_token.as_ref().map(|s| s.as_str())) {
             Ok(loc) => loc,
             Err(e) => {
                 error!("Error getting template: {}", e);
                 return Err(e);
             }
-        };
-        from
+        }
+    } else {
+        let metadata = fs::metadata(&template);
+        if metadata.is_ok() && metadata.unwrap().is_dir() {
+            debug!("found template on filesystem");
+            Path::new(&template).to_path_buf()
+        } else {
+            let index = get_index(&mut config, frozen)?;
+            let location = match index.get(&template) {
+                Some(loc) => loc,
+                None => {
+                    let suggestions = template_suggestions(&template, &index);
+                    return Err(ErrorKind::TemplateDoesNotExist(template.clone(), suggestions).into());
+                }
+            };
+            debug!("template url is {:?}", location);
+            match get_template(&template, location, &config.templates_path, frozen, config.registry_token.as_ref().map(|s| s.as_str())) {
+                Ok(loc) => loc,
+                Err(e) => {
+                    error!("Error getting template: {}", e);
+                    return Err(e);
+                }
+            }
+        }
     };
-    debug!("creating project at {:?}", project_dir);
-    copy_dir(&from, &project_dir)?;
-    debug!("substituting name & author values");
     // open new Cargo.toml && change the name & author lines
     let (author_name, author_email) = get_name_and_email()?;
     debug!("using author info `({:?}, {:?})`", author_name, author_email);
+
+    // Build the substitution context: built-ins first, then the template's
+    // own declared variables (prompting as needed).
+    let manifest = load_manifest(&from)?;
+    let mut context = builtin_context(project_name, &format_author(&author_name, &author_email));
+    resolve_variables(&manifest, &mut context, &defines, frozen)?;
+
+    // Decide whether hook scripts are permitted to run. Fetching a template
+    // from the index shouldn't silently execute code, so hooks require an
+    // explicit opt-in and are refused entirely under --frozen.
+    let has_hooks = !manifest.hooks.pre.is_empty() || !manifest.hooks.post.is_empty();
+    let run_hooks_enabled = matches.is_present("allow-hooks");
+    if has_hooks {
+        if run_hooks_enabled && frozen {
+            warn!("template ships hook scripts but --frozen refuses to run them");
+        } else if !run_hooks_enabled {
+            warn!("template ships hook scripts; pass --allow-hooks to run them");
+        }
+    }
+    let run_hooks_enabled = run_hooks_enabled && !frozen;
+
+    ensure_exists(&project_dir)?;
+    if run_hooks_enabled {
+        run_hooks(&manifest.hooks.pre, &from, &project_dir, &context)?;
+    }
+
+    let rules = TemplateRules::compile(&manifest.template)?;
+
+    debug!("creating project at {:?}", project_dir);
+    copy_dir(&from, &project_dir, &from, &rules, &context)?;
+    debug!("substituting name & author values");
     find_cargo_toml(&project_dir, &project_name, &author_name, &author_email)?;
+
+    // Post hooks run after substitution but before the VCS init, so that
+    // anything they change (e.g. `cargo fmt`) is captured by the initial
+    // commit rather than left dirty in the generated tree.
+    if run_hooks_enabled {
+        run_hooks(&manifest.hooks.post, &from, &project_dir, &context)?;
+    }
+
+    if matches.value_of("vcs").unwrap_or("git") != "none" {
+        debug!("initializing git repository");
+        let is_bin = project_is_bin(&project_dir);
+        init_vcs(&project_dir, &author_name, &author_email, is_bin)?;
+    }
     Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ctx(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn render_substitutes_known_keys_and_trims_whitespace() {
+        let ctx = ctx(&[("name", "widget"), ("crate_name", "wid_get")]);
+        assert_eq!(render("use {{ crate_name }};", &ctx), "use wid_get;");
+        assert_eq!(render("{{name}}-{{ name }}", &ctx), "widget-widget");
+    }
+
+    #[test]
+    fn render_leaves_unknown_placeholders_untouched() {
+        let ctx = ctx(&[("name", "widget")]);
+        assert_eq!(render("{{ missing }} {{ name }}", &ctx), "{{ missing }} widget");
+    }
+
+    #[test]
+    fn render_passes_through_unterminated_braces() {
+        let ctx = ctx(&[("name", "widget")]);
+        assert_eq!(render("plain {{ name with no close", &ctx),
+                   "plain {{ name with no close");
+    }
+
+    fn rules(include: &[&str], exclude: &[&str]) -> TemplateRules {
+        let section = TemplateSection {
+            include: include.iter().map(|s| s.to_string()).collect(),
+            exclude: exclude.iter().map(|s| s.to_string()).collect(),
+        };
+        TemplateRules::compile(&section).unwrap()
+    }
+
+    #[test]
+    fn matches_defaults_to_included_without_rules() {
+        let rules = rules(&[], &[]);
+        assert!(rules.matches(Path::new("src/main.rs")));
+    }
+
+    #[test]
+    fn include_matches_nested_paths_by_component() {
+        let rules = rules(&["*.rs"], &[]);
+        assert!(rules.matches(Path::new("src/main.rs")));
+        assert!(rules.matches(Path::new("main.rs")));
+        assert!(!rules.matches(Path::new("README.md")));
+    }
+
+    #[test]
+    fn exclude_wins_over_include() {
+        let rules = rules(&["*.rs"], &["Cargo.lock"]);
+        assert!(!rules.matches(Path::new("sub/Cargo.lock")));
+        assert!(rules.matches(Path::new("src/lib.rs")));
+    }
+
+    #[test]
+    fn include_list_drops_unmatched() {
+        let rules = rules(&["*.rs"], &[]);
+        assert!(!rules.matches(Path::new("ci/matrix.yml")));
+    }
+
+    #[test]
+    fn levenshtein_basic_distances() {
+        assert_eq!(levenshtein("", ""), 0);
+        assert_eq!(levenshtein("abc", "abc"), 0);
+        assert_eq!(levenshtein("", "abc"), 3);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        assert_eq!(levenshtein("axum", "axm"), 1);
+    }
+
+    #[test]
+    fn suggestions_sorted_by_distance_within_threshold() {
+        let index: HashMap<String, String> = [
+            ("axum", ""), ("actix", ""), ("rocket", ""),
+        ].iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect();
+        let suggestions = template_suggestions("axm", &index);
+        assert_eq!(suggestions, vec!["axum".to_string()]);
+    }
 }
\ No newline at end of file